@@ -0,0 +1,123 @@
+use std::fs::OpenOptions;
+use std::io;
+use std::os::unix::io::AsRawFd;
+use std::thread;
+use std::time::Duration;
+
+const LOCK_PATHS: &[&str] = &[
+    "/var/lib/dpkg/lock-frontend",
+    "/var/lib/dpkg/lock",
+    "/var/lib/apt/lists/lock",
+    "/var/cache/apt/archives/lock",
+];
+
+/// What `wait_for_apt_locks` reports to its `readiness` callback each time
+/// it probes the dpkg/apt locks.
+#[derive(Debug, Clone, Copy)]
+pub struct LockStatus {
+    /// `true` once all locks are free and `func` is about to run.
+    pub ready: bool,
+    /// The PID holding a busy lock, if the kernel could identify it via
+    /// `F_GETLK`. `None` if the lock isn't held via `fcntl`, or the owner
+    /// couldn't be determined.
+    pub blocked_by: Option<libc::pid_t>,
+}
+
+/// The result of probing a single lock file.
+enum LockState {
+    Free,
+    Busy(Option<libc::pid_t>),
+}
+
+/// Wait until none of the dpkg/apt lock files are held, then run `func`.
+///
+/// Each lock file is probed with a non-blocking `fcntl` write lock, and the
+/// descriptor is closed immediately after each probe so we never leak fds
+/// across retries. If a lock is busy, `readiness` is called with the blocking PID
+/// (when it can be determined) and we back off for `backoff_ms` before
+/// probing again. When every lock is free, `func` runs at once, with no
+/// initial wait.
+pub fn wait_for_apt_locks<T, L: FnMut(LockStatus), F: FnOnce() -> io::Result<T>>(
+    backoff_ms: u64,
+    mut readiness: L,
+    func: F,
+) -> io::Result<T> {
+    loop {
+        let mut blocked_by = None;
+
+        for path in LOCK_PATHS {
+            if let LockState::Busy(pid) = probe_lock(path)? {
+                blocked_by = Some(pid);
+                break;
+            }
+        }
+
+        match blocked_by {
+            None => {
+                readiness(LockStatus { ready: true, blocked_by: None });
+                return func();
+            }
+            Some(pid) => {
+                readiness(LockStatus { ready: false, blocked_by: pid });
+                thread::sleep(Duration::from_millis(backoff_ms));
+            }
+        }
+    }
+}
+
+/// Open `path` and attempt a non-blocking POSIX `fcntl` write lock — the
+/// same locking mechanism dpkg and apt use on these files (a BSD `flock`
+/// would succeed independently of their `fcntl` locks and lie about the
+/// file being free) — releasing it immediately and closing the file again
+/// before returning, so the fd is never held across the caller's backoff
+/// sleep. A missing lock file means that subsystem isn't in use, so it's
+/// treated as free.
+fn probe_lock(path: &str) -> io::Result<LockState> {
+    let file = match OpenOptions::new().read(true).write(true).open(path) {
+        Ok(file) => file,
+        Err(ref why) if why.kind() == io::ErrorKind::NotFound => return Ok(LockState::Free),
+        Err(why) => return Err(why),
+    };
+
+    let fd = file.as_raw_fd();
+
+    let mut lock = whole_file_lock(libc::F_WRLCK);
+
+    if unsafe { libc::fcntl(fd, libc::F_SETLK, &mut lock) } == 0 {
+        let mut unlock = whole_file_lock(libc::F_UNLCK);
+        unsafe {
+            libc::fcntl(fd, libc::F_SETLK, &mut unlock);
+        }
+        return Ok(LockState::Free);
+    }
+
+    let why = io::Error::last_os_error();
+    match why.kind() {
+        io::ErrorKind::WouldBlock | io::ErrorKind::PermissionDenied => Ok(LockState::Busy(lock_owner(fd))),
+        _ => Err(why),
+    }
+}
+
+/// Build an `F_SETLK`/`F_GETLK` record covering the whole file.
+fn whole_file_lock(l_type: libc::c_int) -> libc::flock {
+    libc::flock {
+        l_type: l_type as libc::c_short,
+        l_whence: libc::SEEK_SET as libc::c_short,
+        l_start: 0,
+        l_len: 0,
+        l_pid: 0,
+    }
+}
+
+/// Ask the kernel who holds `fd`'s lock via `F_GETLK`.
+fn lock_owner(fd: libc::c_int) -> Option<libc::pid_t> {
+    let mut lock = whole_file_lock(libc::F_WRLCK);
+
+    let got_lock_info = unsafe { libc::fcntl(fd, libc::F_GETLK, &mut lock) } == 0;
+
+    if got_lock_info && lock.l_type as libc::c_int != libc::F_UNLCK {
+        Some(lock.l_pid)
+    } else {
+        None
+    }
+}
@@ -0,0 +1,82 @@
+use crate::misc::check_output;
+use std::io;
+use std::path::Path;
+
+/// Metadata read from a local `.deb` file's control information, via
+/// `dpkg-deb --field`.
+#[derive(Debug, Clone)]
+pub struct DebControl {
+    pub package: String,
+    pub version: String,
+    pub architecture: String,
+}
+
+/// Read a `.deb`'s Package/Version/Architecture control fields without
+/// installing it, so a caller can confirm what's about to happen.
+pub fn deb_control<P: AsRef<Path>>(path: P) -> io::Result<DebControl> {
+    let path = path.as_ref();
+    if !path.is_file() {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("no such file: {}", path.display()),
+        ));
+    }
+
+    let output = check_output("dpkg-deb", |cmd| {
+        cmd.arg("--field").arg(path).arg("Package").arg("Version").arg("Architecture")
+    })?;
+
+    let mut package = None;
+    let mut version = None;
+    let mut architecture = None;
+
+    for line in output.lines() {
+        let mut fields = line.splitn(2, ':');
+        let key = fields.next().unwrap_or_default().trim();
+        let value = fields.next().unwrap_or_default().trim().to_owned();
+        match key {
+            "Package" => package = Some(value),
+            "Version" => version = Some(value),
+            "Architecture" => architecture = Some(value),
+            _ => (),
+        }
+    }
+
+    match (package, version, architecture) {
+        (Some(package), Some(version), Some(architecture)) => {
+            Ok(DebControl { package, version, architecture })
+        }
+        _ => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("incomplete control data for {}", path.display()),
+        )),
+    }
+}
+
+/// Check that a `.deb`'s architecture is installable on this system, per
+/// `dpkg --print-architecture` and `dpkg --print-foreign-architectures`,
+/// so a mismatch surfaces early as a clear error rather than deep inside
+/// an apt transaction.
+pub fn check_architecture(control: &DebControl) -> io::Result<()> {
+    if control.architecture == "all" {
+        return Ok(());
+    }
+
+    let native = check_output("dpkg", |cmd| cmd.arg("--print-architecture"))?;
+    if native.trim() == control.architecture {
+        return Ok(());
+    }
+
+    let foreign = check_output("dpkg", |cmd| cmd.arg("--print-foreign-architectures"))?;
+    if foreign.lines().any(|arch| arch.trim() == control.architecture) {
+        return Ok(());
+    }
+
+    Err(io::Error::new(
+        io::ErrorKind::InvalidInput,
+        format!(
+            "{} is built for architecture {}, which this system does not support",
+            control.package, control.architecture
+        ),
+    ))
+}
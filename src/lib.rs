@@ -1,12 +1,19 @@
 mod apt_lock;
+mod deb;
 mod misc;
 mod predepends;
+mod transaction;
 mod upgrade_event;
 
 pub use self::{
-    apt_lock::wait_for_apt_locks,
-    predepends::{predepends_of, PreDependsIter},
-    upgrade_event::AptUpgradeEvent,
+    apt_lock::{wait_for_apt_locks, LockStatus},
+    deb::{deb_control, DebControl},
+    predepends::{
+        manually_installed, predepends_of, why_installed, DependencyLink, DependencyRelation,
+        PreDependsIter,
+    },
+    transaction::{apt_simulate, AptTransaction, SimulateOp, TransactionItem, TransactionItemKind},
+    upgrade_event::{AptProgress, AptStatusEvent, AptUpgradeEvent, ProgressStage},
 };
 
 use self::misc::check_output;
@@ -16,7 +23,8 @@ use std::{
     ffi::OsStr,
     fs::File,
     io::{self, BufRead, BufReader},
-    os::unix::io::{FromRawFd, IntoRawFd},
+    os::unix::io::{AsRawFd, FromRawFd, IntoRawFd},
+    path::{Path, PathBuf},
     process::{Command, Stdio},
     thread,
     time::Duration,
@@ -63,13 +71,67 @@ pub fn apt_noninteractive_callback<F: FnMut(&mut Command) -> &mut Command, C: Fn
     }
 }
 
+/// Same as `apt_noninteractive`, but reads machine-readable progress
+/// records off apt's dedicated `APT::Status-Fd` pipe instead of parsing
+/// `--show-progress`'s human, locale-dependent stdout. Gives exact
+/// per-package download/unpack percentages.
+pub fn apt_noninteractive_status_fd<
+    F: FnOnce(&mut Command) -> &mut Command,
+    C: Fn(AptStatusEvent),
+>(
+    func: F,
+    callback: C,
+) -> io::Result<()> {
+    let (read, write) = status_fd_pipe()?;
+
+    let mut child = func(
+        Command::new("apt-get")
+            .env("DEBIAN_FRONTEND", "noninteractive")
+            .env("LANG", "C")
+            .args(&["-y", "--allow-downgrades"])
+            .arg(format!("-oAPT::Status-Fd={}", write.as_raw_fd())),
+    )
+    .spawn()?;
+
+    drop(write);
+
+    let mut buffer = String::new();
+    let mut reader = BufReader::new(non_blocking(read));
+
+    loop {
+        thread::sleep(Duration::from_millis(16));
+        match child.try_wait()? {
+            Some(status) => return status.as_result(),
+            None => {
+                let _ = non_blocking_line_reading(&mut reader, &mut buffer, |line| {
+                    if let Ok(event) = line.parse::<AptStatusEvent>() {
+                        callback(event);
+                    }
+                });
+            }
+        }
+    }
+}
+
+/// Open a pipe for a child process's `--status-fd`/`APT::Status-Fd`, as an
+/// already-open `File` pair so the write end's raw fd can be passed as a
+/// command-line argument and inherited across `exec`.
+fn status_fd_pipe() -> io::Result<(File, File)> {
+    let mut fds = [0; 2];
+    if unsafe { libc::pipe(fds.as_mut_ptr()) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let [read, write] = fds;
+    Ok(unsafe { (File::from_raw_fd(read), File::from_raw_fd(write)) })
+}
+
 // apt-autoremove -y
-pub fn apt_autoremove<L: FnMut(bool)>(readiness: L) -> io::Result<()> {
+pub fn apt_autoremove<L: FnMut(LockStatus)>(readiness: L) -> io::Result<()> {
     wait_for_apt_locks(3000, readiness, || apt_noninteractive(|cmd| cmd.arg("autoremove")))
 }
 
 /// apt-cache subcommand package...
-pub fn apt_cache<L: FnMut(bool)>(
+pub fn apt_cache<L: FnMut(LockStatus)>(
     subcommand: &str,
     packages: &[&str],
     readiness: L,
@@ -79,34 +141,87 @@ pub fn apt_cache<L: FnMut(bool)>(
 }
 
 /// apt-get -y --allow-downgrades install
-pub fn apt_install<L: FnMut(bool)>(packages: &[&str], readiness: L) -> io::Result<()> {
+pub fn apt_install<L: FnMut(LockStatus)>(packages: &[&str], readiness: L) -> io::Result<()> {
     wait_for_apt_locks(3000, readiness, || {
         apt_noninteractive(move |cmd| cmd.arg("install").args(packages))
     })
 }
 
-pub fn apt_install_fix_broken<L: FnMut(bool)>(readiness: L) -> io::Result<()> {
+/// A target for `apt_install_versioned`: a plain package, a package pinned
+/// to an exact version, or a path to a local `.deb` file.
+#[derive(Debug, Clone)]
+pub enum InstallTarget {
+    Name(String),
+    Versioned { name: String, version: String },
+    File(PathBuf),
+}
+
+impl InstallTarget {
+    fn to_arg(&self) -> String {
+        match self {
+            InstallTarget::Name(name) => name.clone(),
+            InstallTarget::Versioned { name, version } => format!("{}={}", name, version),
+            InstallTarget::File(path) => local_deb_arg(path),
+        }
+    }
+}
+
+/// apt only recognizes a target as a local file if it contains a `/`; a
+/// bare `foo.deb` would otherwise be looked up as a package name, so a
+/// path with no directory component is given an explicit `./` prefix.
+fn local_deb_arg(path: &Path) -> String {
+    let path = path.display().to_string();
+    if path.contains('/') {
+        path
+    } else {
+        format!("./{}", path)
+    }
+}
+
+/// apt-get -y --allow-downgrades install, accepting version-pinned
+/// (`pkg=1.2.3-1`) and local `.deb` file targets alongside plain names.
+///
+/// Local `.deb` targets are checked for existence and architecture
+/// compatibility up front, so a mismatch surfaces as a clear `io::Error`
+/// instead of an apt failure partway through the transaction.
+pub fn apt_install_versioned<L: FnMut(LockStatus)>(
+    targets: &[InstallTarget],
+    readiness: L,
+) -> io::Result<()> {
+    for target in targets {
+        if let InstallTarget::File(path) = target {
+            self::deb::check_architecture(&self::deb::deb_control(path)?)?;
+        }
+    }
+
+    let args: Vec<String> = targets.iter().map(InstallTarget::to_arg).collect();
+    wait_for_apt_locks(3000, readiness, || {
+        apt_noninteractive(|cmd| cmd.arg("install").args(&args))
+    })
+}
+
+pub fn apt_install_fix_broken<L: FnMut(LockStatus)>(readiness: L) -> io::Result<()> {
     wait_for_apt_locks(3000, readiness, || {
         apt_noninteractive(move |cmd| cmd.args(&["install", "-f"]))
     })
 }
 
 /// apt-get -y --allow-downgrades purge
-pub fn apt_purge<L: FnMut(bool)>(packages: &[&str], readiness: L) -> io::Result<()> {
+pub fn apt_purge<L: FnMut(LockStatus)>(packages: &[&str], readiness: L) -> io::Result<()> {
     wait_for_apt_locks(3000, readiness, || {
         apt_noninteractive(move |cmd| cmd.arg("purge").args(packages))
     })
 }
 
 /// apt-get -y --allow-downgrades install --reinstall
-pub fn apt_reinstall<L: FnMut(bool)>(packages: &[&str], readiness: L) -> io::Result<()> {
+pub fn apt_reinstall<L: FnMut(LockStatus)>(packages: &[&str], readiness: L) -> io::Result<()> {
     wait_for_apt_locks(3000, readiness, || {
         apt_noninteractive(move |cmd| cmd.arg("install").arg("--reinstall").args(packages))
     })
 }
 
 /// apt-get remove --autoremove -y
-pub fn apt_remove<I: IntoIterator<Item = S>, S: AsRef<OsStr>, L: FnMut(bool)>(
+pub fn apt_remove<I: IntoIterator<Item = S>, S: AsRef<OsStr>, L: FnMut(LockStatus)>(
     packages: I,
     readiness: L,
 ) -> io::Result<()> {
@@ -115,22 +230,77 @@ pub fn apt_remove<I: IntoIterator<Item = S>, S: AsRef<OsStr>, L: FnMut(bool)>(
     })
 }
 
+/// A single install or removal to fold into a batched `apt_update_list` call.
+#[derive(Debug, Clone)]
+pub enum PackageOp {
+    Install { name: String, version: Option<String>, file: Option<PathBuf> },
+    Remove { name: String },
+}
+
+impl PackageOp {
+    /// The argument apt expects for this op when appended to `install ...`:
+    /// a plain name, `pkg=version`, a `.deb` path, or `pkg-` for removal.
+    /// apt has no versioned-remove syntax, so `Remove` carries no version.
+    fn to_arg(&self) -> String {
+        match self {
+            PackageOp::Install { file: Some(file), .. } => local_deb_arg(file),
+            PackageOp::Install { name, version: Some(version), .. } => format!("{}={}", name, version),
+            PackageOp::Install { name, .. } => name.clone(),
+            PackageOp::Remove { name } => format!("{}-", name),
+        }
+    }
+}
+
+/// apt-get -y --allow-downgrades install, with a mix of installs and
+/// removals resolved together in a single invocation so apt can pick one
+/// consistent dependency solution across the whole set.
+pub fn apt_update_list<L: FnMut(LockStatus)>(ops: &[PackageOp], readiness: L) -> io::Result<()> {
+    let args: Vec<String> = ops.iter().map(PackageOp::to_arg).collect();
+    wait_for_apt_locks(3000, readiness, || {
+        apt_noninteractive(|cmd| cmd.arg("install").args(&args))
+    })
+}
+
 /// apt-get -y --allow-downgrades full-upgrade
-pub fn apt_update<L: FnMut(bool)>(readiness: L) -> io::Result<()> {
+pub fn apt_update<L: FnMut(LockStatus)>(readiness: L) -> io::Result<()> {
     wait_for_apt_locks(3000, readiness, || apt_noninteractive(|cmd| cmd.arg("update")))
 }
 
-/// apt-get -y --allow-downgrades full-upgrade
-pub fn apt_upgrade<C: Fn(AptUpgradeEvent)>(callback: C) -> io::Result<()> {
+/// Which of apt's resolver behaviors to use when upgrading.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UpgradeMode {
+    /// `apt-get upgrade` — upgrade installed packages, never install new
+    /// ones or remove anything.
+    Standard,
+    /// `apt-get upgrade --with-new-pkgs` — like `Standard`, but may pull in
+    /// new packages to satisfy dependencies. Still never removes anything.
+    WithNewPackages,
+    /// `apt-get full-upgrade` — upgrade installed packages, allowing
+    /// installs and removals as needed to resolve the upgrade.
+    Full,
+}
+
+impl UpgradeMode {
+    fn args(self) -> &'static [&'static str] {
+        match self {
+            UpgradeMode::Standard => &["upgrade"],
+            UpgradeMode::WithNewPackages => &["upgrade", "--with-new-pkgs"],
+            UpgradeMode::Full => &["full-upgrade"],
+        }
+    }
+}
+
+/// apt-get -y --allow-downgrades, in the given `UpgradeMode`.
+pub fn apt_upgrade<C: Fn(AptUpgradeEvent)>(mode: UpgradeMode, callback: C) -> io::Result<()> {
     let callback = &callback;
-    let readiness = |ready: bool| {
-        if !ready {
+    let readiness = |status: LockStatus| {
+        if !status.ready {
             callback(AptUpgradeEvent::WaitingOnLock)
         }
     };
     wait_for_apt_locks(3000, readiness, || {
         apt_noninteractive_callback(
-            |cmd| cmd.args(&["--show-progress", "full-upgrade"]),
+            |cmd| cmd.arg("--show-progress").args(mode.args()),
             move |line| {
                 if let Ok(event) = line.parse::<AptUpgradeEvent>() {
                     callback(event);
@@ -140,14 +310,43 @@ pub fn apt_upgrade<C: Fn(AptUpgradeEvent)>(callback: C) -> io::Result<()> {
     })
 }
 
-/// dpkg --configure -a
-pub fn dpkg_configure_all<L: FnMut(bool)>(readiness: L) -> io::Result<()> {
-    // TODO: progress callback support.
+/// Back-compat shim for the old, always-`full-upgrade` behavior of `apt_upgrade`.
+pub fn full_upgrade<C: Fn(AptUpgradeEvent)>(callback: C) -> io::Result<()> {
+    apt_upgrade(UpgradeMode::Full, callback)
+}
+
+/// dpkg --configure -a, reporting `status:`/`processing:` records off
+/// dpkg's `--status-fd` as `AptStatusEvent`s.
+pub fn dpkg_configure_all<L: FnMut(LockStatus), C: Fn(AptStatusEvent)>(
+    readiness: L,
+    callback: C,
+) -> io::Result<()> {
     wait_for_apt_locks(3000, readiness, || {
-        Command::new("dpkg")
+        let (read, write) = status_fd_pipe()?;
+
+        let mut child = Command::new("dpkg")
             .args(&["--configure", "-a"])
-            .status()
-            .and_then(ExitStatusExt::as_result)
+            .arg(format!("--status-fd={}", write.as_raw_fd()))
+            .spawn()?;
+
+        drop(write);
+
+        let mut buffer = String::new();
+        let mut reader = BufReader::new(non_blocking(read));
+
+        loop {
+            thread::sleep(Duration::from_millis(16));
+            match child.try_wait()? {
+                Some(status) => return status.as_result(),
+                None => {
+                    let _ = non_blocking_line_reading(&mut reader, &mut buffer, |line| {
+                        if let Ok(event) = line.parse::<AptStatusEvent>() {
+                            callback(event);
+                        }
+                    });
+                }
+            }
+        }
     })
 }
 
@@ -0,0 +1,15 @@
+use std::io;
+use std::process::Command;
+
+/// Run `cmd`, built up by `func`, and return its captured stdout as a `String`.
+pub fn check_output<F: FnOnce(&mut Command) -> &mut Command>(
+    cmd: &str,
+    func: F,
+) -> io::Result<String> {
+    func(&mut Command::new(cmd))
+        .output()
+        .and_then(|output| {
+            String::from_utf8(output.stdout)
+                .map_err(|why| io::Error::new(io::ErrorKind::InvalidData, why))
+        })
+}
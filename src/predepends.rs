@@ -0,0 +1,239 @@
+use crate::misc::check_output;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::io;
+use std::vec::IntoIter;
+
+/// Iterator over the immediate Pre-Depends of a package, by name only
+/// (version constraints and alternatives are dropped).
+pub struct PreDependsIter {
+    names: IntoIter<String>,
+}
+
+impl Iterator for PreDependsIter {
+    type Item = String;
+
+    fn next(&mut self) -> Option<String> {
+        self.names.next()
+    }
+}
+
+/// List the immediate Pre-Depends of `package`, as reported by
+/// `dpkg-query -f '${pre-depends}'`.
+pub fn predepends_of(package: &str) -> PreDependsIter {
+    let output =
+        check_output("dpkg-query", |cmd| cmd.arg("-f").arg("${pre-depends}").arg("-W").arg(package))
+            .unwrap_or_default();
+
+    let names = output
+        .split(',')
+        .filter_map(|entry| {
+            entry.trim().split(|c: char| c.is_whitespace() || c == '(' || c == '|').next()
+        })
+        .map(str::to_owned)
+        .filter(|name| !name.is_empty())
+        .collect::<Vec<_>>()
+        .into_iter();
+
+    PreDependsIter { names }
+}
+
+/// The kind of dependency relation linking two packages in a
+/// `why_installed` chain.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DependencyRelation {
+    Depends,
+    PreDepends,
+}
+
+/// One edge in a `why_installed` chain: `from` depends (via `relation`) on
+/// `to`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DependencyLink {
+    pub from: String,
+    pub relation: DependencyRelation,
+    pub to: String,
+}
+
+/// List packages marked as manually installed, via `apt-mark showmanual`.
+/// These are the roots a `why_installed` search terminates at.
+pub fn manually_installed(buffer: &mut String) -> impl Iterator<Item = &str> {
+    *buffer = check_output("apt-mark", |cmd| cmd.arg("showmanual")).unwrap_or_default();
+    buffer.lines().filter(|line| !line.is_empty())
+}
+
+/// Explain why `package` is installed, similar to `aptitude why`: the
+/// shortest chain of reverse dependencies from some manually-installed
+/// package down to `package`. Returns `Ok(Some(vec![]))` if `package` is
+/// itself manually installed, and `Ok(None)` if no chain can be found
+/// (e.g. `package` isn't installed, or nothing manually installed depends
+/// on it).
+pub fn why_installed(package: &str) -> io::Result<Option<Vec<DependencyLink>>> {
+    let mut manual_buffer = String::new();
+    let roots: HashSet<String> = manually_installed(&mut manual_buffer).map(str::to_owned).collect();
+
+    if roots.contains(package) {
+        return Ok(Some(Vec::new()));
+    }
+
+    let mut visited = HashSet::new();
+    let mut queue = VecDeque::new();
+    let mut prev: HashMap<String, DependencyLink> = HashMap::new();
+
+    visited.insert(package.to_owned());
+    queue.push_back(package.to_owned());
+
+    while let Some(current) = queue.pop_front() {
+        for (parent, relation) in reverse_dependencies(&current)? {
+            if !visited.insert(parent.clone()) {
+                continue;
+            }
+
+            prev.insert(
+                parent.clone(),
+                DependencyLink { from: parent.clone(), relation, to: current.clone() },
+            );
+
+            if roots.contains(&parent) {
+                return Ok(Some(reconstruct_path(&prev, &parent)));
+            }
+
+            queue.push_back(parent);
+        }
+    }
+
+    Ok(None)
+}
+
+/// Walk `prev` from `root` forward to `package`, collecting the links
+/// along the way in root-to-target order.
+fn reconstruct_path(prev: &HashMap<String, DependencyLink>, root: &str) -> Vec<DependencyLink> {
+    let mut path = Vec::new();
+    let mut node = root.to_owned();
+
+    while let Some(link) = prev.get(&node) {
+        node = link.to.clone();
+        path.push(link.clone());
+    }
+
+    path
+}
+
+/// List the packages that directly depend on `package`, via
+/// `apt-cache rdepends`, classifying each edge as `Depends` or
+/// `PreDepends` by checking `predepends_of` on the depending package.
+fn reverse_dependencies(package: &str) -> io::Result<Vec<(String, DependencyRelation)>> {
+    let output = check_output("apt-cache", |cmd| {
+        cmd.arg("rdepends")
+            .arg("--installed")
+            .args(&["--no-recommends", "--no-suggests", "--no-conflicts", "--no-breaks"])
+            .args(&["--no-replaces", "--no-enhances"])
+            .arg(package)
+    })?;
+
+    Ok(parse_rdepends(&output, package)
+        .into_iter()
+        .map(|name| {
+            let relation = if predepends_of(&name).any(|dep| dep == package) {
+                DependencyRelation::PreDepends
+            } else {
+                DependencyRelation::Depends
+            };
+            (name, relation)
+        })
+        .collect())
+}
+
+/// Parse the package names out of `apt-cache rdepends --installed <package>`
+/// output, dropping the `Reverse Depends:` header, the echoed `package`
+/// itself, the `(nothing)` placeholder apt prints when there are no
+/// reverse dependencies, and the `|` alternative markers.
+fn parse_rdepends(output: &str, package: &str) -> Vec<String> {
+    let mut in_list = false;
+    let mut names = Vec::new();
+
+    for line in output.lines() {
+        let line = line.trim();
+
+        if line == "Reverse Depends:" {
+            in_list = true;
+            continue;
+        }
+
+        if !in_list || line.is_empty() || line == package || line == "(nothing)" {
+            continue;
+        }
+
+        let name = line.trim_start_matches('|').to_owned();
+        if !name.is_empty() {
+            names.push(name);
+        }
+    }
+
+    names
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rdepends_output_is_parsed() {
+        let output = "pkg\n\
+                       Reverse Depends:\n  \
+                       depender-a\n  \
+                       |depender-b\n  \
+                       pkg\n";
+
+        assert_eq!(parse_rdepends(output, "pkg"), vec!["depender-a", "depender-b"]);
+    }
+
+    #[test]
+    fn rdepends_output_without_header_is_empty() {
+        assert_eq!(parse_rdepends("pkg\n", "pkg"), Vec::<String>::new());
+    }
+
+    #[test]
+    fn rdepends_output_with_no_reverse_deps_is_empty() {
+        let output = "pkg\nReverse Depends:\n  (nothing)\n";
+        assert_eq!(parse_rdepends(output, "pkg"), Vec::<String>::new());
+    }
+
+    #[test]
+    fn path_is_reconstructed_root_to_target() {
+        let mut prev = HashMap::new();
+        prev.insert(
+            "root".to_owned(),
+            DependencyLink {
+                from: "root".to_owned(),
+                relation: DependencyRelation::Depends,
+                to: "middle".to_owned(),
+            },
+        );
+        prev.insert(
+            "middle".to_owned(),
+            DependencyLink {
+                from: "middle".to_owned(),
+                relation: DependencyRelation::PreDepends,
+                to: "target".to_owned(),
+            },
+        );
+
+        let path = reconstruct_path(&prev, "root");
+
+        assert_eq!(
+            path,
+            vec![
+                DependencyLink {
+                    from: "root".to_owned(),
+                    relation: DependencyRelation::Depends,
+                    to: "middle".to_owned(),
+                },
+                DependencyLink {
+                    from: "middle".to_owned(),
+                    relation: DependencyRelation::PreDepends,
+                    to: "target".to_owned(),
+                },
+            ]
+        );
+    }
+}
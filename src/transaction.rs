@@ -0,0 +1,228 @@
+use self::TransactionItemKind as Kind;
+use crate::misc::check_output;
+use std::io;
+
+/// The action apt plans to take on a single package within a transaction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TransactionItemKind {
+    Install,
+    Remove,
+    Purge,
+    Upgrade,
+    Downgrade,
+    Configure,
+}
+
+/// A single package action parsed out of `apt-get -s` output.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TransactionItem {
+    pub name: String,
+    pub version: Option<String>,
+    pub kind: TransactionItemKind,
+}
+
+/// A parsed preview of what `apt-get` would do, grouped by action so a
+/// caller can render a confirmation dialog before committing to it.
+#[derive(Debug, Clone, Default)]
+pub struct AptTransaction {
+    pub install: Vec<TransactionItem>,
+    pub remove: Vec<TransactionItem>,
+    pub purge: Vec<TransactionItem>,
+    pub upgrade: Vec<TransactionItem>,
+    pub downgrade: Vec<TransactionItem>,
+    pub held_back: Vec<TransactionItem>,
+}
+
+impl AptTransaction {
+    fn push(&mut self, item: TransactionItem) {
+        match item.kind {
+            Kind::Install => self.install.push(item),
+            Kind::Remove => self.remove.push(item),
+            Kind::Purge => self.purge.push(item),
+            Kind::Upgrade => self.upgrade.push(item),
+            Kind::Downgrade => self.downgrade.push(item),
+            Kind::Configure => (),
+        }
+    }
+}
+
+/// The apt-get subcommand that `apt_simulate` should preview.
+#[derive(Debug, Clone, Copy)]
+pub enum SimulateOp {
+    Install,
+    Remove,
+    Purge,
+    FullUpgrade,
+}
+
+impl SimulateOp {
+    fn subcommand(self) -> &'static str {
+        match self {
+            SimulateOp::Install => "install",
+            SimulateOp::Remove => "remove",
+            SimulateOp::Purge => "purge",
+            SimulateOp::FullUpgrade => "full-upgrade",
+        }
+    }
+}
+
+/// Run `apt-get -s <op> <packages>` and parse the resulting plan without
+/// touching the system, so a caller can show a confirmation preview first.
+pub fn apt_simulate(packages: &[&str], op: SimulateOp) -> io::Result<AptTransaction> {
+    let output = check_output("apt-get", |cmd| {
+        cmd.env("LANG", "C").arg("-s").arg(op.subcommand()).args(packages)
+    })?;
+
+    Ok(parse_transaction(&output))
+}
+
+/// Parse the full stdout of `apt-get -s` into an `AptTransaction`.
+fn parse_transaction(output: &str) -> AptTransaction {
+    let mut transaction = AptTransaction::default();
+    let mut lines = output.lines().peekable();
+
+    while let Some(line) = lines.next() {
+        if let Some(item) = parse_action_line(line) {
+            transaction.push(item);
+        } else if line.starts_with("The following packages have been kept back") {
+            while let Some(next) = lines.peek() {
+                if !next.starts_with(' ') {
+                    break;
+                }
+                for name in lines.next().unwrap().split_whitespace() {
+                    transaction.held_back.push(TransactionItem {
+                        name: name.to_owned(),
+                        version: None,
+                        kind: Kind::Upgrade,
+                    });
+                }
+            }
+        }
+    }
+
+    transaction
+}
+
+/// Parse a single `Inst pkg [old] (new repo [arch])`, `Remv pkg (...)`, or
+/// `Conf pkg (...)` line from `apt-get -s` output.
+fn parse_action_line(line: &str) -> Option<TransactionItem> {
+    let mut fields = line.splitn(2, ' ');
+    let verb = fields.next()?;
+    let rest = fields.next()?.trim();
+
+    let mut base_kind = match verb {
+        "Inst" => Kind::Install,
+        "Remv" => Kind::Remove,
+        "Purg" => Kind::Purge,
+        "Conf" => Kind::Configure,
+        _ => return None,
+    };
+
+    let name = rest.split(|c: char| c.is_whitespace() || c == '[' || c == '(').next()?.to_owned();
+
+    // The `[old-version]` apt prints for an upgrade/downgrade comes before
+    // the `(new-version repo [arch])` parens; a `[...]` found only inside
+    // those parens is the architecture, not a version, so it must not be
+    // mistaken for one.
+    let current_version = match (rest.find('['), rest.find('(')) {
+        (Some(bracket), Some(paren)) if bracket < paren => bracketed(rest, '[', ']'),
+        (Some(_), None) => bracketed(rest, '[', ']'),
+        _ => None,
+    };
+    let new_version = parenthesized(rest).and_then(|inner| inner.split_whitespace().next().map(str::to_owned));
+
+    if base_kind == Kind::Install {
+        if let (Some(current), Some(ref new)) = (&current_version, &new_version) {
+            base_kind = if version_is_older(new, current) { Kind::Downgrade } else { Kind::Upgrade };
+        }
+    }
+
+    Some(TransactionItem { name, version: new_version.or(current_version), kind: base_kind })
+}
+
+fn bracketed(s: &str, open: char, close: char) -> Option<String> {
+    let start = s.find(open)? + 1;
+    let end = start + s[start..].find(close)?;
+    Some(s[start..end].to_owned())
+}
+
+fn parenthesized(s: &str) -> Option<&str> {
+    let start = s.find('(')? + 1;
+    let end = start + s[start..].find(')')?;
+    Some(&s[start..end])
+}
+
+/// Best-effort version comparison: split on non-alphanumeric separators and
+/// compare piece by piece, numerically where both pieces are numbers and
+/// lexicographically otherwise; if every compared piece is equal, the
+/// shorter version string counts as older. This is not a full dpkg version
+/// comparator, just enough to tell an upgrade from a downgrade for preview
+/// purposes.
+fn version_is_older(candidate: &str, current: &str) -> bool {
+    fn pieces(v: &str) -> Vec<&str> {
+        v.split(|c: char| !c.is_ascii_alphanumeric()).filter(|s| !s.is_empty()).collect()
+    }
+
+    for (a, b) in pieces(candidate).into_iter().zip(pieces(current)) {
+        match (a.parse::<u64>(), b.parse::<u64>()) {
+            (Ok(a), Ok(b)) if a != b => return a < b,
+            _ if a != b => return a < b,
+            _ => continue,
+        }
+    }
+
+    candidate.len() < current.len()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fresh_install_is_not_a_downgrade() {
+        let item = parse_action_line("Inst newpkg (1.0 focal [amd64])").unwrap();
+        assert_eq!(item.kind, Kind::Install);
+        assert_eq!(item.name, "newpkg");
+        assert_eq!(item.version.as_deref(), Some("1.0"));
+    }
+
+    #[test]
+    fn upgrade_is_classified_correctly() {
+        let item = parse_action_line("Inst pkg [1.0] (2.0 focal [amd64])").unwrap();
+        assert_eq!(item.kind, Kind::Upgrade);
+        assert_eq!(item.version.as_deref(), Some("2.0"));
+    }
+
+    #[test]
+    fn downgrade_is_classified_correctly() {
+        let item = parse_action_line("Inst pkg [2.0] (1.0 focal [amd64])").unwrap();
+        assert_eq!(item.kind, Kind::Downgrade);
+        assert_eq!(item.version.as_deref(), Some("1.0"));
+    }
+
+    #[test]
+    fn remove_purge_and_configure_lines() {
+        assert_eq!(parse_action_line("Remv pkg (1.0 focal [amd64])").unwrap().kind, Kind::Remove);
+        assert_eq!(parse_action_line("Purg pkg (1.0 focal [amd64])").unwrap().kind, Kind::Purge);
+        assert_eq!(parse_action_line("Conf pkg (1.0 focal [amd64])").unwrap().kind, Kind::Configure);
+    }
+
+    #[test]
+    fn unrecognized_lines_are_ignored() {
+        assert!(parse_action_line("Reading package lists...").is_none());
+    }
+
+    #[test]
+    fn held_back_section_is_parsed() {
+        let output = "Inst newpkg (1.0 focal [amd64])\n\
+                       The following packages have been kept back:\n  \
+                       pkga pkgb\n\
+                       0 upgraded, 1 newly installed";
+
+        let transaction = parse_transaction(output);
+
+        assert_eq!(transaction.install.len(), 1);
+        let held_back: Vec<_> = transaction.held_back.iter().map(|item| item.name.as_str()).collect();
+        assert_eq!(held_back, vec!["pkga", "pkgb"]);
+    }
+}
@@ -0,0 +1,230 @@
+use std::str::FromStr;
+
+/// A coarse event parsed out of `apt-get --show-progress`'s human-readable
+/// stdout. Locale-dependent, but good enough for a simple progress bar.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AptUpgradeEvent {
+    /// Waiting for another process to release the dpkg/apt locks.
+    WaitingOnLock,
+    /// `Progress: [ NN%]`.
+    Progress { percent: u8 },
+    /// `Unpacking pkg (new) over (old) ...`.
+    Unpacking { package: String, version: String, over: String },
+    /// `Setting up pkg (version) ...`.
+    SettingUp { package: String },
+    /// `Processing triggers for pkg ...`.
+    Processing { package: String },
+}
+
+impl FromStr for AptUpgradeEvent {
+    type Err = ();
+
+    fn from_str(line: &str) -> Result<Self, Self::Err> {
+        let line = line.trim();
+
+        if let Some(rest) = line.strip_prefix("Progress: [") {
+            let percent = rest.trim_start().trim_end_matches("%]").trim().parse().map_err(|_| ())?;
+            return Ok(AptUpgradeEvent::Progress { percent });
+        }
+
+        if let Some(rest) = line.strip_prefix("Unpacking ") {
+            let mut fields = rest.splitn(2, " over ");
+            let installing = fields.next().ok_or(())?;
+            let over = fields.next().ok_or(())?;
+
+            let (package, version) = package_and_parens(installing)?;
+            let (_, over) = package_and_parens(over)?;
+
+            return Ok(AptUpgradeEvent::Unpacking { package, version, over });
+        }
+
+        if let Some(rest) = line.strip_prefix("Setting up ") {
+            let (package, _) = package_and_parens(rest)?;
+            return Ok(AptUpgradeEvent::SettingUp { package });
+        }
+
+        if let Some(rest) = line.strip_prefix("Processing triggers for ") {
+            let (package, _) = package_and_parens(rest)?;
+            return Ok(AptUpgradeEvent::Processing { package });
+        }
+
+        Err(())
+    }
+}
+
+/// Split `"pkg (1.2.3) ..."` into `("pkg", "1.2.3")`.
+fn package_and_parens(s: &str) -> Result<(String, String), ()> {
+    let package = s.split_whitespace().next().ok_or(())?.to_owned();
+    let start = s.find('(').ok_or(())? + 1;
+    let end = start + s[start..].find(')').ok_or(())?;
+    Ok((package, s[start..end].to_owned()))
+}
+
+/// Which phase a machine-readable `AptProgress` record came from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProgressStage {
+    /// `dlstatus:` — downloading package files.
+    Download,
+    /// `pmstatus:` — unpacking or configuring.
+    Unpack,
+}
+
+/// A precise, locale-independent progress update read from apt's
+/// `APT::Status-Fd` pipe.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AptProgress {
+    pub package: String,
+    pub percent: f32,
+    pub stage: ProgressStage,
+    pub message: String,
+}
+
+/// A record read from apt's `APT::Status-Fd` pipe, or dpkg's `--status-fd`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum AptStatusEvent {
+    Progress(AptProgress),
+    /// `pmconffile:` — apt is asking what to do about a changed conffile.
+    ConfFile { package: String, message: String },
+    /// `media-change:` — apt is asking for removable media to be swapped.
+    MediaChange { message: String },
+    /// dpkg `status: <pkg>: <status>`.
+    DpkgStatus { package: String, status: String },
+    /// dpkg `processing: <stage>: <pkg>`.
+    DpkgProcessing { stage: String, package: String },
+}
+
+impl FromStr for AptStatusEvent {
+    type Err = ();
+
+    fn from_str(line: &str) -> Result<Self, Self::Err> {
+        let line = line.trim();
+
+        if let Some(rest) = line.strip_prefix("dlstatus:") {
+            return parse_status_fields(rest, ProgressStage::Download).map(AptStatusEvent::Progress);
+        }
+
+        if let Some(rest) = line.strip_prefix("pmstatus:") {
+            return parse_status_fields(rest, ProgressStage::Unpack).map(AptStatusEvent::Progress);
+        }
+
+        if let Some(rest) = line.strip_prefix("pmconffile:") {
+            let mut fields = rest.splitn(2, ':');
+            let package = fields.next().ok_or(())?.to_owned();
+            let message = fields.next().unwrap_or_default().to_owned();
+            return Ok(AptStatusEvent::ConfFile { package, message });
+        }
+
+        if let Some(rest) = line.strip_prefix("media-change:") {
+            return Ok(AptStatusEvent::MediaChange { message: rest.to_owned() });
+        }
+
+        if let Some(rest) = line.strip_prefix("status:") {
+            let mut fields = rest.splitn(2, ':');
+            let package = fields.next().ok_or(())?.trim().to_owned();
+            let status = fields.next().ok_or(())?.trim().to_owned();
+            return Ok(AptStatusEvent::DpkgStatus { package, status });
+        }
+
+        if let Some(rest) = line.strip_prefix("processing:") {
+            let mut fields = rest.splitn(2, ':');
+            let stage = fields.next().ok_or(())?.trim().to_owned();
+            let package = fields.next().ok_or(())?.trim().to_owned();
+            return Ok(AptStatusEvent::DpkgProcessing { stage, package });
+        }
+
+        Err(())
+    }
+}
+
+/// Parse the `<pkg>:<percent>:<description>` tail shared by `dlstatus` and
+/// `pmstatus` records.
+fn parse_status_fields(rest: &str, stage: ProgressStage) -> Result<AptProgress, ()> {
+    let mut fields = rest.splitn(3, ':');
+    let package = fields.next().ok_or(())?.to_owned();
+    let percent = fields.next().ok_or(())?.trim().parse().map_err(|_| ())?;
+    let message = fields.next().unwrap_or_default().trim().to_owned();
+    Ok(AptProgress { package, percent, stage, message })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn upgrade_event_lines() {
+        let cases = [
+            ("Progress: [ 42%]", AptUpgradeEvent::Progress { percent: 42 }),
+            (
+                "Unpacking pkg (2.0) over (1.0) ...",
+                AptUpgradeEvent::Unpacking {
+                    package: "pkg".into(),
+                    version: "2.0".into(),
+                    over: "1.0".into(),
+                },
+            ),
+            ("Setting up pkg (2.0) ...", AptUpgradeEvent::SettingUp { package: "pkg".into() }),
+            (
+                "Processing triggers for pkg (2.0) ...",
+                AptUpgradeEvent::Processing { package: "pkg".into() },
+            ),
+        ];
+
+        for (line, expected) in cases {
+            assert_eq!(line.parse::<AptUpgradeEvent>().unwrap(), expected, "parsing {:?}", line);
+        }
+    }
+
+    #[test]
+    fn unrecognized_upgrade_event_line_is_err() {
+        assert!("Reading package lists...".parse::<AptUpgradeEvent>().is_err());
+    }
+
+    #[test]
+    fn status_fd_lines() {
+        let cases = [
+            (
+                "dlstatus:pkg:37.5:Downloading pkg",
+                AptStatusEvent::Progress(AptProgress {
+                    package: "pkg".into(),
+                    percent: 37.5,
+                    stage: ProgressStage::Download,
+                    message: "Downloading pkg".into(),
+                }),
+            ),
+            (
+                "pmstatus:pkg:80:Installing pkg",
+                AptStatusEvent::Progress(AptProgress {
+                    package: "pkg".into(),
+                    percent: 80.0,
+                    stage: ProgressStage::Unpack,
+                    message: "Installing pkg".into(),
+                }),
+            ),
+            (
+                "pmconffile:pkg:/etc/pkg.conf",
+                AptStatusEvent::ConfFile { package: "pkg".into(), message: "/etc/pkg.conf".into() },
+            ),
+            (
+                "media-change:Please insert disc 2",
+                AptStatusEvent::MediaChange { message: "Please insert disc 2".into() },
+            ),
+            (
+                "status: pkg: installed",
+                AptStatusEvent::DpkgStatus { package: "pkg".into(), status: "installed".into() },
+            ),
+            (
+                "processing: configure: pkg",
+                AptStatusEvent::DpkgProcessing { stage: "configure".into(), package: "pkg".into() },
+            ),
+        ];
+
+        for (line, expected) in cases {
+            assert_eq!(line.parse::<AptStatusEvent>().unwrap(), expected, "parsing {:?}", line);
+        }
+    }
+
+    #[test]
+    fn unrecognized_status_fd_line_is_err() {
+        assert!("not a status line".parse::<AptStatusEvent>().is_err());
+    }
+}